@@ -0,0 +1,45 @@
+use kvs::client::KvsClient;
+use kvs::Result;
+use std::net::SocketAddr;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = env!("CARGO_PKG_NAME"), about = env!("CARGO_PKG_DESCRIPTION"), author = env!("CARGO_PKG_AUTHORS"), version = env!("CARGO_PKG_VERSION"))]
+enum Opt {
+    Set {
+        key: String,
+        value: String,
+        #[structopt(long, default_value = "127.0.0.1:4000")]
+        addr: SocketAddr,
+    },
+    Get {
+        key: String,
+        #[structopt(long, default_value = "127.0.0.1:4000")]
+        addr: SocketAddr,
+    },
+    Rm {
+        key: String,
+        #[structopt(long, default_value = "127.0.0.1:4000")]
+        addr: SocketAddr,
+    },
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    match opt {
+        Opt::Set { key, value, addr } => {
+            KvsClient::connect(addr)?.set(key, value)?;
+        }
+        Opt::Get { key, addr } => match KvsClient::connect(addr)?.get(key)? {
+            Some(value) => println!("{}", value),
+            None => println!("Key not found"),
+        },
+        Opt::Rm { key, addr } => {
+            if let Err(kvs::Error::KeyNotFound) = KvsClient::connect(addr)?.remove(key) {
+                println!("Key not found");
+                std::process::exit(1)
+            }
+        }
+    }
+    Ok(())
+}
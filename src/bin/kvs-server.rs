@@ -0,0 +1,26 @@
+use kvs::engine::{select_engine, Engine};
+use kvs::server::KvsServer;
+use kvs::sled_engine::SledKvsEngine;
+use kvs::{KvStore, Result};
+use std::net::SocketAddr;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = env!("CARGO_PKG_NAME"), about = env!("CARGO_PKG_DESCRIPTION"), author = env!("CARGO_PKG_AUTHORS"), version = env!("CARGO_PKG_VERSION"))]
+struct Opt {
+    #[structopt(long, default_value = "127.0.0.1:4000")]
+    addr: SocketAddr,
+    /// Storage engine to serve: "kvs" or "sled". Defaults to whichever engine the
+    /// current directory was already created with, or "kvs" for a new one.
+    #[structopt(long)]
+    engine: Option<Engine>,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let dir = std::env::current_dir()?;
+    match select_engine(&dir, opt.engine)? {
+        Engine::Kvs => KvsServer::new(KvStore::open(&dir)?).run(opt.addr),
+        Engine::Sled => KvsServer::new(SledKvsEngine::open(&dir)?).run(opt.addr),
+    }
+}
@@ -1,27 +1,72 @@
+use kvs::engine::{select_engine, Engine, KvsEngine};
+use kvs::sled_engine::SledKvsEngine;
 use kvs::{KvStore, Result};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 #[structopt(name = env!("CARGO_PKG_NAME"), about = env!("CARGO_PKG_DESCRIPTION"), author = env!("CARGO_PKG_AUTHORS"), version = env!("CARGO_PKG_VERSION"))]
 enum Opt {
-    Set { key: String, value: String },
-    Get { key: String },
-    Rm { key: String },
+    Set {
+        key: String,
+        value: String,
+        /// Storage engine to use: "kvs" or "sled".
+        #[structopt(long)]
+        engine: Option<Engine>,
+    },
+    Get {
+        key: String,
+        /// Storage engine to use: "kvs" or "sled".
+        #[structopt(long)]
+        engine: Option<Engine>,
+    },
+    Rm {
+        key: String,
+        /// Storage engine to use: "kvs" or "sled".
+        #[structopt(long)]
+        engine: Option<Engine>,
+    },
+    /// Write every key/value pair to a portable, gzip-compressed snapshot. Only
+    /// supported by the "kvs" engine.
+    Dump { file: PathBuf },
+    /// Replace the store's contents with a snapshot written by `dump`. Only supported
+    /// by the "kvs" engine.
+    Restore { file: PathBuf },
+    /// Migrate a store written by an older version of kvs to the current format. Only
+    /// supported by the "kvs" engine.
+    Upgrade,
+}
+
+// Opens whichever engine `current_dir` is (or should be) stamped with, so Set/Get/Rm
+// work the same regardless of backend.
+fn open_engine(requested: Option<Engine>) -> Result<Box<dyn KvsEngine>> {
+    let dir = std::env::current_dir()?;
+    match select_engine(&dir, requested)? {
+        Engine::Kvs => Ok(Box::new(KvStore::open(&dir)?)),
+        Engine::Sled => Ok(Box::new(SledKvsEngine::open(&dir)?)),
+    }
+}
+
+// Dump/restore/upgrade work directly on the log, so they only make sense against the
+// kvs engine; this errors out instead of silently ignoring a directory stamped "sled".
+fn open_kvs_store() -> Result<KvStore> {
+    let dir = std::env::current_dir()?;
+    select_engine(&dir, Some(Engine::Kvs))?;
+    KvStore::open(&dir)
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let mut kvs = KvStore::open("")?;
     match opt {
-        Opt::Set { key, value } => {
-            kvs.set(key, value)?;
+        Opt::Set { key, value, engine } => {
+            open_engine(engine)?.set(key, value)?;
         }
-        Opt::Get { key } => match kvs.get(key)? {
+        Opt::Get { key, engine } => match open_engine(engine)?.get(key)? {
             Some(value) => println!("{}", value),
             None => println!("Key not found"),
         },
-        Opt::Rm { key } => {
-            if let Err(err) = kvs.remove(key) {
+        Opt::Rm { key, engine } => {
+            if let Err(err) = open_engine(engine)?.remove(key) {
                 match err {
                     kvs::Error::KeyNotFound => {
                         println!("Key not found");
@@ -31,6 +76,15 @@ fn main() -> Result<()> {
                 }
             }
         }
+        Opt::Dump { file } => {
+            open_kvs_store()?.dump(file)?;
+        }
+        Opt::Restore { file } => {
+            open_kvs_store()?.restore(file)?;
+        }
+        Opt::Upgrade => {
+            open_kvs_store()?.upgrade()?;
+        }
     }
     Ok(())
 }
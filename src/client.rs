@@ -0,0 +1,50 @@
+use crate::{read_framed, write_framed, Command, Error, Response, Result};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// KvsClient sends `set`/`get`/`remove` commands to a `kvs-server`, one per connection.
+pub struct KvsClient {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl KvsClient {
+    /// Connects to a `kvs-server` listening at `addr`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<KvsClient> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(KvsClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    /// Sets a value associated with key.
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.request(Command::Set { key, value }).map(|_| ())
+    }
+
+    /// Gets a value by key.
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.request(Command::Get { key })
+    }
+
+    /// Removes a value by key.
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.request(Command::Remove { key }).map(|_| ())
+    }
+
+    fn request(&mut self, command: Command) -> Result<Option<String>> {
+        write_framed(&mut self.writer, &command)?;
+        self.writer.flush()?;
+        match read_framed(&mut self.reader)? {
+            Response::Ok(value) => Ok(value),
+            // The server can only send an error message back as a String; recognize the
+            // one case the CLI treats specially (a non-zero exit on Rm of a missing key)
+            // by its rendered Display text, same as Error::KeyNotFound would have had.
+            Response::Err(msg) if msg == Error::KeyNotFound.to_string() => {
+                Err(Error::KeyNotFound)
+            }
+            Response::Err(msg) => Err(Error::UnexpectedError(msg)),
+        }
+    }
+}
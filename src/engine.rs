@@ -0,0 +1,84 @@
+use crate::{Error, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A storage backend that can serve a `kvs`/`kvs-server` process's set/get/remove
+/// commands. Implemented by the crate's own log-structured `KvStore` as well as by
+/// `SledKvsEngine`, a thin wrapper over the `sled` embedded database.
+pub trait KvsEngine {
+    /// Set a value associated with key.
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+    /// Get a value by key.
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+    /// Remove a value by key.
+    fn remove(&mut self, key: String) -> Result<()>;
+}
+
+const ENGINE_FILE_NAME: &str = "engine";
+
+/// Identifies which `KvsEngine` implementation a `kvs`/`kvs-server` process is using, as
+/// named on its `--engine` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The crate's own bitcask-style log store, `KvStore`.
+    Kvs,
+    /// `SledKvsEngine`, backed by the `sled` embedded database.
+    Sled,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "kvs" => Ok(Engine::Kvs),
+            "sled" => Ok(Engine::Sled),
+            other => Err(format!(
+                "unknown engine {:?}, expected \"kvs\" or \"sled\"",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Engine::Kvs => "kvs",
+            Engine::Sled => "sled",
+        })
+    }
+}
+
+/// Resolves which engine a process rooted at `dir` should run with: `requested` if
+/// given, otherwise whichever engine previously stamped `dir`, otherwise `Engine::Kvs`.
+/// Stamps `dir` with the result, and refuses to reopen a directory with an engine other
+/// than the one it was first created with.
+pub fn select_engine(dir: &Path, requested: Option<Engine>) -> Result<Engine> {
+    let stamp_path = dir.join(ENGINE_FILE_NAME);
+    let stamped = match fs::read_to_string(&stamp_path) {
+        Ok(contents) => Some(
+            contents
+                .trim()
+                .parse::<Engine>()
+                .map_err(Error::UnexpectedError)?,
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let engine = match (requested, stamped) {
+        (Some(requested), Some(stamped)) if requested != stamped => {
+            return Err(Error::UnexpectedError(format!(
+                "{} was requested, but {:?} was already created with {}",
+                requested, dir, stamped
+            )))
+        }
+        (Some(requested), _) => requested,
+        (None, Some(stamped)) => stamped,
+        (None, None) => Engine::Kvs,
+    };
+    fs::write(&stamp_path, engine.to_string())?;
+    Ok(engine)
+}
@@ -1,208 +1,701 @@
 //! kvs provides a key-vale store in memory.
 #![deny(missing_docs)]
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
 use std::io::{self, prelude::*};
-use std::{fs, path::Path};
+use std::{fs, path::Path, path::PathBuf};
 
-/// KvStore is an in-memory key-value store.
+/// A `kvs-client` for talking to a `kvs-server` over its length-framed wire protocol.
+pub mod client;
+/// The `KvsEngine` trait that every storage backend implements, plus the `Engine`
+/// selector `kvs`/`kvs-server` use to pick one.
+pub mod engine;
+/// A `kvs-server` that serves a `KvsEngine` over the network.
+pub mod server;
+/// `SledKvsEngine`, a `KvsEngine` backed by the `sled` embedded database.
+pub mod sled_engine;
+
+/// KvStore is a key-value store backed by a set of append-only log segments on disk.
 pub struct KvStore {
-    datafile: std::path::PathBuf,
+    path: PathBuf,
+    // Readers of the log segments, one per generation, opened when the store is opened or
+    // a segment is created and kept around for subsequent reads.
+    readers: HashMap<u64, BufReaderWithPos<fs::File>>,
     writer: PositionedWriter<io::BufWriter<fs::File>>,
-    /// a map from key to log pointer which is  represented as a file offset.
-    index: BTreeMap<String, u64>,
-    num_dead_keys: u64,
+    current_gen: u64,
+    /// a map from key to log pointer which is represented as a segment and byte offset.
+    index: BTreeMap<String, CommandPos>,
+    // number of bytes in the log segments that could be freed by a compaction.
+    uncompacted: u64,
+    // monotonically increasing count of index hints written by this store, persisted
+    // into the hint file alongside the index it describes.
+    seq: u64,
 }
 
 use serde::{Deserialize, Serialize};
 
+// Command is both the on-disk log record and the wire request sent by a kvs-client;
+// `Get` is only ever used on the wire, since a get is never itself logged.
 #[derive(Serialize, Deserialize, Debug)]
-enum Command {
+pub(crate) enum Command {
     Set { key: String, value: String },
     Remove { key: String },
+    Get { key: String },
+}
+
+// Response is what a kvs-server sends back for a single Command.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Response {
+    Ok(Option<String>),
+    Err(String),
+}
+
+const LOG_FILE_EXT: &str = "log";
+
+// Compact once the stale bytes in the log segments cross this threshold.
+const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+// Every record is framed as [u32 length][u32 crc32] followed by that many bytes of
+// JSON, so a torn or corrupted record can be detected and skipped during replay.
+const RECORD_HEADER_LEN: u64 = 8;
+
+// A record's length prefix is untrusted input on the kvs-server wire protocol (any
+// client can send one), so it's capped well above any real Command/Response before
+// being trusted to size an allocation.
+const MAX_RECORD_LEN: u32 = 8 * 1024 * 1024;
+
+/// CommandPos points at a single command within a log segment: which segment (`gen`),
+/// the byte offset it starts at (`pos`), and how many bytes it occupies (`len`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CommandPos {
+    gen: u64,
+    pos: u64,
+    len: u64,
+}
+
+const HINT_FILE_NAME: &str = "index.hint";
+
+// Bumped if the on-disk shape of `IndexHint` ever changes, so an old hint file is
+// recognized as unreadable instead of misinterpreted.
+const HINT_FORMAT_VERSION: u32 = 2;
+
+// The persisted form of the in-memory index, written on a clean close or after a
+// compaction so the next open() can skip replaying the whole log.
+//
+// Staleness is decided by `last_gen`/`last_gen_len`: the generation and exact byte
+// length of the active segment at the moment the hint was written. Any write since
+// then either appended to that same segment (growing its length past `last_gen_len`)
+// or rolled over to a newer generation (via compaction), so comparing those two
+// recorded values against what's on disk now detects every case deterministically
+// rather than relying on filesystem mtime resolution, which a write landing in the
+// same tick as a previous flush could fool. `seq` is a monotonically increasing count
+// of hints this store has written, for debugging; it isn't itself the staleness guard.
+#[derive(Serialize, Deserialize)]
+struct IndexHint {
+    format_version: u32,
+    seq: u64,
+    uncompacted: u64,
+    last_gen: u64,
+    last_gen_len: u64,
+    index: BTreeMap<String, CommandPos>,
+}
+
+// Bumped if the shape of a dump's header or body ever changes, so `restore` can refuse
+// a dump it doesn't know how to read instead of misinterpreting it.
+const DUMP_FORMAT_VERSION: u32 = 1;
+
+// The first line of a dump file, before the newline-delimited `Command::Set` records.
+#[derive(Serialize, Deserialize)]
+struct DumpHeader {
+    dump_version: u32,
+    db_version: String,
+    created_at: u64,
 }
 
-const DATA_FILE_NAME: &str = "datafile";
+const FORMAT_FILE_NAME: &str = "format";
 
-const COMPACTION_DEAD_KEYS_RATIO: f64 = 0.4;
+// The on-disk format `open` writes and understands. Bumped whenever a change to the
+// log's byte-level representation (framing, segment layout, ...) isn't backward
+// compatible, so an old binary can refuse a store it would otherwise misread.
+const CURRENT_FORMAT_VERSION: u32 = 1;
 
 impl KvStore {
     /// Opens a `KvStore` from the directory at path.
     ///
     /// This will create a new directory if the given one does not exist.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<KvStore> {
-        fs::create_dir_all(path.as_ref())?;
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+
+        // A directory with no format file yet is either brand new or predates this
+        // versioning scheme; either way today's on-disk bytes are what CURRENT_FORMAT_VERSION
+        // describes, so stamp it rather than refusing to open.
+        let format_version = read_format_version(&path)?.unwrap_or(CURRENT_FORMAT_VERSION);
+        if format_version > CURRENT_FORMAT_VERSION {
+            return Err(Error::UnsupportedFormatVersion {
+                found: format_version,
+                supported: CURRENT_FORMAT_VERSION,
+            });
+        }
+        write_format_version(&path, format_version)?;
 
-        let datafile = path.as_ref().join(DATA_FILE_NAME);
+        let mut readers = HashMap::new();
         let mut index = BTreeMap::new();
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&datafile)?;
-        let offset = file.seek(io::SeekFrom::End(0))?;
+        let mut uncompacted = 0;
+        let mut seq = 0;
+
+        let gen_list = sorted_gen_list(&path)?;
+        if let Some(hint) = load_hint(&path, &gen_list)? {
+            index = hint.index;
+            uncompacted = hint.uncompacted;
+            seq = hint.seq;
+            for &gen in &gen_list {
+                readers.insert(gen, BufReaderWithPos::new(fs::File::open(log_path(&path, gen))?)?);
+            }
+        } else {
+            for &gen in &gen_list {
+                // build_index truncates a torn tail write via set_len, which needs a
+                // writable fd; a read-only open fails that ftruncate with EINVAL.
+                let file = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(log_path(&path, gen))?;
+                let mut reader = BufReaderWithPos::new(file)?;
+                uncompacted += build_index(gen, &mut reader, &mut index)?;
+                readers.insert(gen, reader);
+            }
+        }
 
-        let num_dead_keys = build_index(&datafile, &mut index)?;
+        let current_gen = gen_list.last().unwrap_or(&0) + 1;
+        let writer = new_log_file(&path, current_gen, &mut readers)?;
 
         Ok(KvStore {
-            datafile,
-            writer: PositionedWriter {
-                w: io::BufWriter::new(file),
-                offset,
-            },
+            path,
+            readers,
+            writer,
+            current_gen,
             index,
-            num_dead_keys,
+            uncompacted,
+            seq,
         })
     }
 
+    /// Persists the in-memory index to a hint file so the next `open` can skip
+    /// replaying the log, and flushes the active log segment.
+    ///
+    /// This runs automatically when the store is dropped; call it directly to persist
+    /// the hint sooner, e.g. before a process is about to be killed.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.seq += 1;
+        write_hint(
+            &self.path,
+            self.seq,
+            self.uncompacted,
+            self.current_gen,
+            self.writer.offset,
+            &self.index,
+        )?;
+        Ok(())
+    }
+
     /// Set a value associated with key.
     ///
     /// Commands are serialized in JSON format for easier debugging.
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
         let command = Command::Set { key, value };
-        let offset = self.writer.offset;
-        serde_json::to_writer(&mut self.writer, &command)?;
+        let pos = self.writer.offset;
+        write_command(&mut self.writer, &command)?;
+        self.writer.flush()?;
+        let new_pos = self.writer.offset;
+
         if let Command::Set { key, .. } = command {
-            if let Some(_) = self.index.insert(key, offset) {
-                self.num_dead_keys += 1;
-                self.compact()?;
+            let command_pos = CommandPos {
+                gen: self.current_gen,
+                pos,
+                len: new_pos - pos,
+            };
+            if let Some(old_command) = self.index.insert(key, command_pos) {
+                self.uncompacted += old_command.len;
             }
         }
+
+        if self.uncompacted > COMPACTION_THRESHOLD {
+            self.compact()?;
+        }
         Ok(())
     }
 
     /// Get a value by key.
     pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        self.writer.flush()?;
-        do_get(&self.index, &self.datafile, key)
+        do_get(&mut self.readers, &self.index, key)
     }
 
     /// Remove a value by key.
     pub fn remove(&mut self, key: String) -> Result<()> {
-        match self.index.get(&key) {
-            Some(_) => {
-                let command = Command::Remove { key };
-                serde_json::to_writer(&mut self.writer, &command)?;
-                if let Command::Remove { key } = command {
-                    if let Some(_) = self.index.remove(&key) {
-                        self.num_dead_keys += 1;
-                        self.compact()?;
-                    }
-                }
-                Ok(())
+        if !self.index.contains_key(&key) {
+            return Err(Error::KeyNotFound);
+        }
+
+        let command = Command::Remove { key };
+        let pos = self.writer.offset;
+        write_command(&mut self.writer, &command)?;
+        self.writer.flush()?;
+        let new_pos = self.writer.offset;
+
+        if let Command::Remove { key } = command {
+            if let Some(old_command) = self.index.remove(&key) {
+                self.uncompacted += old_command.len;
             }
-            None => Err(Error::KeyNotFound),
         }
+        self.uncompacted += new_pos - pos;
+
+        if self.uncompacted > COMPACTION_THRESHOLD {
+            self.compact()?;
+        }
+        Ok(())
     }
 
+    // Writes the still-live commands into a fresh generation, then drops the now-stale
+    // segment files so only inactive segments ever get rewritten.
     fn compact(&mut self) -> Result<()> {
-        let dead_keys_ratio = self.num_dead_keys as f64 / self.index.len() as f64;
-        if dead_keys_ratio > COMPACTION_DEAD_KEYS_RATIO {
-            self._compact()?;
+        // The compacted log goes one generation ahead of the current writer; the writer
+        // itself then moves two generations ahead so new writes never land in it.
+        let compaction_gen = self.current_gen + 1;
+        self.current_gen += 2;
+        self.writer = new_log_file(&self.path, self.current_gen, &mut self.readers)?;
+
+        let mut compaction_writer = new_log_file(&self.path, compaction_gen, &mut self.readers)?;
+        let mut new_pos = 0;
+        for command_pos in self.index.values_mut() {
+            let reader = self
+                .readers
+                .get_mut(&command_pos.gen)
+                .expect("Cannot find log reader");
+            if reader.pos != command_pos.pos {
+                reader.seek(io::SeekFrom::Start(command_pos.pos))?;
+            }
+            let mut entry_reader = reader.take(command_pos.len);
+            let len = io::copy(&mut entry_reader, &mut compaction_writer)?;
+            *command_pos = CommandPos {
+                gen: compaction_gen,
+                pos: new_pos,
+                len,
+            };
+            new_pos += len;
+        }
+        compaction_writer.flush()?;
+
+        let stale_gens: Vec<u64> = self
+            .readers
+            .keys()
+            .filter(|&&gen| gen < compaction_gen)
+            .cloned()
+            .collect();
+        for stale_gen in stale_gens {
+            self.readers.remove(&stale_gen);
+            fs::remove_file(log_path(&self.path, stale_gen))?;
         }
+        self.uncompacted = 0;
+        self.flush()?;
         Ok(())
     }
 
-    // TODO need some refactor and address those questions from project-2 about file handling managment and copying.
-    // TODO try to split data into multiple files and only compaction inactive files.
-    fn _compact(&mut self) -> Result<()> {
-        self.writer.flush()?;
-        // Overwrite the data file with new bunch of Command::Set commands based on current index in memory
-        let mut buf = Vec::new();
-        let mut index = BTreeMap::new();
-        let mut writer = PositionedWriter {
-            w: &mut buf,
-            offset: 0,
-        };
-        let mut offset = 0;
-        for key in self.index.keys() {
-            let command = Command::Set {
-                key: key.to_owned(),
-                value: do_get(&self.index, &self.datafile, key.to_owned())?.unwrap(),
-            };
-            serde_json::to_writer(&mut writer, &command)?;
-            // Update index keys with new offset
-            index.insert(key.to_owned(), offset);
-            offset = writer.offset;
-        }
-        self.index = index;
-        // Update data file content
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(&self.datafile)?;
-
-        // Update writer
-        self.writer = PositionedWriter {
-            w: io::BufWriter::new(file),
-            offset: 0,
+    /// Writes every live key/value pair to `path` as a gzip-compressed, portable
+    /// snapshot: a `DumpHeader` line followed by newline-delimited `Command::Set`
+    /// records. Unlike copying the log segments directly, this is independent of the
+    /// store's on-disk layout and contains only the current value of each key.
+    pub fn dump<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = fs::File::create(path.as_ref())?;
+        let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+
+        let header = DumpHeader {
+            dump_version: DUMP_FORMAT_VERSION,
+            db_version: env!("CARGO_PKG_VERSION").to_owned(),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
         };
-        self.writer.write_all(buf.as_ref())?;
+        serde_json::to_writer(&mut encoder, &header)?;
+        encoder.write_all(b"\n")?;
+
+        let keys: Vec<String> = self.index.keys().cloned().collect();
+        for key in keys {
+            if let Some(value) = do_get(&mut self.readers, &self.index, key.clone())? {
+                serde_json::to_writer(&mut encoder, &Command::Set { key, value })?;
+                encoder.write_all(b"\n")?;
+            }
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Replaces this store's contents with the key/value pairs dumped to `path` by
+    /// `dump`, leaving behind a single freshly compacted log segment.
+    pub fn restore<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = fs::File::open(path.as_ref())?;
+        let mut decoder = io::BufReader::new(flate2::read::GzDecoder::new(file));
+
+        let mut header_line = String::new();
+        decoder.read_line(&mut header_line)?;
+        let header: DumpHeader = serde_json::from_str(header_line.trim_end())?;
+        if header.dump_version > DUMP_FORMAT_VERSION {
+            return Err(Error::UnexpectedError(format!(
+                "dump format version {} is newer than this binary's {}",
+                header.dump_version, DUMP_FORMAT_VERSION
+            )));
+        }
+
+        // Parse every record before touching the live store: a dump that's truncated
+        // or corrupted in transit (an ordinary "transfer got cut off" failure) must
+        // fail without having already cleared the data restore was about to replace.
+        let mut records = Vec::new();
+        for line in decoder.lines() {
+            let line = line?;
+            match serde_json::from_str(&line)? {
+                Command::Set { key, value } => records.push((key, value)),
+                _ => {
+                    return Err(Error::UnexpectedError(
+                        "dump contains a command other than Set".to_owned(),
+                    ))
+                }
+            }
+        }
+
+        self.clear()?;
+        for (key, value) in records {
+            self.set(key, value)?;
+        }
+        self.compact()
+    }
+
+    // Discards every existing log segment and hint, leaving a single empty active
+    // segment, so `restore` can replay a dump into a store with no prior state.
+    fn clear(&mut self) -> Result<()> {
+        self.readers.clear();
+        for gen in sorted_gen_list(&self.path)? {
+            fs::remove_file(log_path(&self.path, gen))?;
+        }
+        let hint = hint_path(&self.path);
+        if hint.is_file() {
+            fs::remove_file(hint)?;
+        }
+        self.index.clear();
+        self.uncompacted = 0;
+        self.current_gen = 1;
+        self.writer = new_log_file(&self.path, self.current_gen, &mut self.readers)?;
         Ok(())
     }
+
+    /// Migrates a store written by an older version of this crate to the current
+    /// on-disk format, by reusing the same compaction path that `set`/`remove` trigger,
+    /// then bumping the stored format version. A no-op if the store is already current.
+    pub fn upgrade(&mut self) -> Result<()> {
+        let format_version = read_format_version(&self.path)?.unwrap_or(CURRENT_FORMAT_VERSION);
+        if format_version < CURRENT_FORMAT_VERSION {
+            self.compact()?;
+        }
+        write_format_version(&self.path, CURRENT_FORMAT_VERSION)
+    }
+}
+
+impl Drop for KvStore {
+    fn drop(&mut self) {
+        // Best-effort: a clean close persists the hint so the next open() can skip
+        // replaying the log, but there is nowhere to report a failure from here.
+        let _ = self.flush();
+    }
+}
+
+impl engine::KvsEngine for KvStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.set(key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.get(key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.remove(key)
+    }
+}
+
+// Writes `command` through `writer`'s [length][crc32] framing.
+fn write_command<W: Write>(writer: &mut PositionedWriter<W>, command: &Command) -> Result<()> {
+    write_framed(writer, command)
+}
+
+// Writes `value` as a `[u32 length][u32 crc32]`-framed JSON record. Used both for log
+// records (via `write_command`) and for the kvs-server/kvs-client wire protocol.
+pub(crate) fn write_framed<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    let payload = serde_json::to_vec(value)?;
+    let crc = crc32fast::hash(&payload);
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(&crc.to_be_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+// Reads one `write_framed` record, verifying its crc32. Unlike `read_command` this is
+// used on streams (e.g. a TCP connection) with no recovery semantics of their own, so
+// any mismatch is simply an error.
+pub(crate) fn read_framed<R: Read, T: serde::de::DeserializeOwned>(reader: &mut R) -> Result<T> {
+    let (len, expected_crc) = read_record_header(reader)?;
+    if len > MAX_RECORD_LEN {
+        return Err(Error::UnexpectedError(format!(
+            "frame length {} exceeds max {}",
+            len, MAX_RECORD_LEN
+        )));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    if crc32fast::hash(&payload) != expected_crc {
+        return Err(Error::UnexpectedError("frame checksum mismatch".to_owned()));
+    }
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+// Reads the framed record at the reader's current position, without re-checking its
+// crc32 (the index is only ever built from records that already passed that check).
+fn read_command(reader: &mut impl Read) -> Result<Command> {
+    let (len, _crc) = read_record_header(reader)?;
+    if len > MAX_RECORD_LEN {
+        return Err(Error::UnexpectedError(format!(
+            "frame length {} exceeds max {}",
+            len, MAX_RECORD_LEN
+        )));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+// Reads the `[u32 length][u32 crc32]` header prefixed to every record.
+fn read_record_header(reader: &mut impl Read) -> io::Result<(u32, u32)> {
+    let mut len_buf = [0u8; 4];
+    let mut crc_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf)?;
+    reader.read_exact(&mut crc_buf)?;
+    Ok((u32::from_be_bytes(len_buf), u32::from_be_bytes(crc_buf)))
 }
 
 fn do_get(
-    index: &BTreeMap<String, u64>,
-    datafile: impl AsRef<Path>,
+    readers: &mut HashMap<u64, BufReaderWithPos<fs::File>>,
+    index: &BTreeMap<String, CommandPos>,
     key: String,
 ) -> Result<Option<String>> {
-    let op = index.get(&key);
-    let p = match op {
-        Some(p) => p.clone(),
-        None => {
-            return Ok(None);
-        }
-    };
-    let mut file = fs::File::open(datafile)?;
-    file.seek(io::SeekFrom::Start(p))?;
-    let mut stream = serde_json::Deserializer::from_reader(&file).into_iter::<Command>();
-    let command = match stream.next() {
-        Some(result) => result?,
-        None => {
-            return Err(Error::UnexpectedError(format!(
-                "no command from offset {}",
-                p
-            )))
-        }
+    let command_pos = match index.get(&key) {
+        Some(command_pos) => *command_pos,
+        None => return Ok(None),
     };
 
-    if let Command::Set { key: k, value: v } = command {
-        if k != key {
-            Ok(None)
-        } else {
-            Ok(Some(v))
-        }
-    } else {
-        Err(Error::UnexpectedError(
+    let reader = readers
+        .get_mut(&command_pos.gen)
+        .expect("Cannot find log reader");
+    reader.seek(io::SeekFrom::Start(command_pos.pos))?;
+    match read_command(reader)? {
+        Command::Set { value, .. } => Ok(Some(value)),
+        _ => Err(Error::UnexpectedError(
             "read command is a not Command::Set".to_owned(),
-        ))
+        )),
     }
 }
 
-fn build_index(datafile: impl AsRef<Path>, index: &mut BTreeMap<String, u64>) -> Result<u64> {
-    let mut num_dead_keys = 0;
-    let mut file = fs::File::open(datafile)?;
-    let mut stream = serde_json::Deserializer::from_reader(&mut file).into_iter::<Command>();
-    let mut offset = stream.byte_offset() as u64;
-    while let Some(command) = stream.next() {
-        let command = command?;
-        match command {
+// Replays one log segment into the index, returning the number of bytes in it that are
+// already stale (superseded or removed) so the caller can track `uncompacted`.
+//
+// A record whose header or payload is torn off by a crash mid-write is only ever the
+// last one in the segment; when that happens the log is truncated back to the last
+// valid record instead of failing the whole open(). A crc mismatch anywhere else in
+// the segment means real corruption, not a torn write, and is reported as an error.
+fn build_index(
+    gen: u64,
+    reader: &mut BufReaderWithPos<fs::File>,
+    index: &mut BTreeMap<String, CommandPos>,
+) -> Result<u64> {
+    let total_len = reader.seek(io::SeekFrom::End(0))?;
+    let mut pos = reader.seek(io::SeekFrom::Start(0))?;
+    let mut uncompacted = 0;
+
+    while pos < total_len {
+        let (len, expected_crc) = match read_record_header(reader) {
+            Ok(header) => header,
+            Err(_) => break,
+        };
+        let len = len as u64;
+
+        // A length claiming more bytes than remain in the segment can only be a torn
+        // write to the tail (there's nothing past end-of-file to have corrupted this
+        // into the middle of), so it's handled the same way as a short read below,
+        // without first allocating a payload buffer sized off untrusted input.
+        if len > total_len.saturating_sub(pos + RECORD_HEADER_LEN) {
+            break;
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        if reader.read_exact(&mut payload).is_err() {
+            break;
+        }
+
+        if crc32fast::hash(&payload) != expected_crc {
+            if pos + RECORD_HEADER_LEN + len == total_len {
+                break;
+            }
+            return Err(Error::Corruption { gen, offset: pos });
+        }
+
+        let new_pos = pos + RECORD_HEADER_LEN + len;
+        match serde_json::from_slice(&payload)? {
             Command::Set { key, .. } => {
-                if let Some(_) = index.insert(key, offset as u64) {
-                    num_dead_keys += 1;
+                let command_pos = CommandPos {
+                    gen,
+                    pos,
+                    len: new_pos - pos,
+                };
+                if let Some(old_command) = index.insert(key, command_pos) {
+                    uncompacted += old_command.len;
                 }
             }
             Command::Remove { key } => {
-                if let Some(_) = index.remove(&key) {
-                    num_dead_keys += 1;
+                if let Some(old_command) = index.remove(&key) {
+                    uncompacted += old_command.len;
                 }
-                // Because the remove command will always be deleted in a compaction.
-                num_dead_keys += 1;
+                // The remove command itself will always be dropped in a compaction.
+                uncompacted += new_pos - pos;
+            }
+            Command::Get { .. } => {
+                return Err(Error::UnexpectedError(
+                    "log contains a Get command, which is never persisted".to_owned(),
+                ))
             }
         }
-        offset = stream.byte_offset() as u64;
+        pos = new_pos;
+    }
+
+    if pos < total_len {
+        reader.r.get_ref().set_len(pos)?;
+        reader.seek(io::SeekFrom::Start(pos))?;
+    }
+
+    Ok(uncompacted)
+}
+
+// Returns the sorted generation numbers of the log segments present in `path`.
+fn sorted_gen_list(path: &Path) -> Result<Vec<u64>> {
+    let mut gen_list: Vec<u64> = fs::read_dir(path)?
+        .flat_map(|res| -> Result<_> { Ok(res?.path()) })
+        .filter(|path| path.is_file() && path.extension() == Some(OsStr::new(LOG_FILE_EXT)))
+        .flat_map(|path| path.file_stem().and_then(OsStr::to_str).map(str::parse::<u64>))
+        .flatten()
+        .collect();
+    gen_list.sort_unstable();
+    Ok(gen_list)
+}
+
+fn log_path(dir: &Path, gen: u64) -> PathBuf {
+    dir.join(format!("{}.{}", gen, LOG_FILE_EXT))
+}
+
+fn hint_path(dir: &Path) -> PathBuf {
+    dir.join(HINT_FILE_NAME)
+}
+
+fn format_path(dir: &Path) -> PathBuf {
+    dir.join(FORMAT_FILE_NAME)
+}
+
+// Reads the version stamped into the `format` file, or `None` if the store predates it.
+fn read_format_version(dir: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(format_path(dir)) {
+        Ok(contents) => contents
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::UnexpectedError(format!("unreadable format file: {:?}", contents))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_format_version(dir: &Path, version: u32) -> Result<()> {
+    fs::write(format_path(dir), version.to_string())?;
+    Ok(())
+}
+
+// Loads the persisted index from the hint file in `path`, unless it is missing, of an
+// unrecognized format, or stale: the newest log segment on disk is not the one it
+// recorded, or no longer has the byte length it recorded (so a write happened after it).
+fn load_hint(path: &Path, gen_list: &[u64]) -> Result<Option<IndexHint>> {
+    let file = match fs::File::open(hint_path(path)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let hint = match serde_json::from_reader::<_, IndexHint>(file) {
+        Ok(hint) if hint.format_version == HINT_FORMAT_VERSION => hint,
+        _ => return Ok(None),
+    };
+
+    // The hint is stale if a write has moved the active segment on since it was
+    // written: either the segment list no longer ends where it did, or that last
+    // segment's byte length has grown past what the hint recorded.
+    if gen_list.last() != Some(&hint.last_gen) {
+        return Ok(None);
+    }
+    match fs::metadata(log_path(path, hint.last_gen)) {
+        Ok(metadata) if metadata.len() == hint.last_gen_len => Ok(Some(hint)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
     }
-    Ok(num_dead_keys)
+}
+
+fn write_hint(
+    path: &Path,
+    seq: u64,
+    uncompacted: u64,
+    last_gen: u64,
+    last_gen_len: u64,
+    index: &BTreeMap<String, CommandPos>,
+) -> Result<()> {
+    let hint = IndexHint {
+        format_version: HINT_FORMAT_VERSION,
+        seq,
+        uncompacted,
+        last_gen,
+        last_gen_len,
+        index: index.clone(),
+    };
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(hint_path(path))?;
+    serde_json::to_writer(file, &hint)?;
+    Ok(())
+}
+
+// Creates a new log segment for `gen`, registers a reader for it, and returns a writer
+// positioned at its start.
+fn new_log_file(
+    path: &Path,
+    gen: u64,
+    readers: &mut HashMap<u64, BufReaderWithPos<fs::File>>,
+) -> Result<PositionedWriter<io::BufWriter<fs::File>>> {
+    let path = log_path(path, gen);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)?;
+    let writer = PositionedWriter {
+        w: io::BufWriter::new(file),
+        offset: 0,
+    };
+    readers.insert(gen, BufReaderWithPos::new(fs::File::open(&path)?)?);
+    Ok(writer)
 }
 
 /// PositionedWriter tracks the current writing position as a offset in bytes from the start of the stream.
@@ -223,6 +716,38 @@ impl<W: io::Write> io::Write for PositionedWriter<W> {
     }
 }
 
+// BufReaderWithPos tracks the current reading position so compaction can skip redundant
+// seeks when reading commands in index order.
+struct BufReaderWithPos<R: Read> {
+    r: io::BufReader<R>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> BufReaderWithPos<R> {
+    fn new(mut inner: R) -> Result<Self> {
+        let pos = inner.stream_position()?;
+        Ok(BufReaderWithPos {
+            r: io::BufReader::new(inner),
+            pos,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for BufReaderWithPos<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for BufReaderWithPos<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.pos = self.r.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
 /// Result type for kvs.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -243,12 +768,146 @@ pub enum Error {
     /// Unexpected error.
     #[error("Unexpected error: {0}")]
     UnexpectedError(String),
+    /// A record's crc32 did not match its payload part-way through a log segment,
+    /// i.e. not the torn-write-on-the-tail case that `open` recovers from on its own.
+    #[error("corrupted record in segment {gen}, offset {offset}")]
+    Corruption {
+        /// Generation of the log segment containing the corrupt record.
+        gen: u64,
+        /// Byte offset of the corrupt record within that segment.
+        offset: u64,
+    },
+    /// The store on disk was written by a newer version of this crate than is running.
+    #[error("store format version {found} is newer than this binary's {supported}; upgrade kvs")]
+    UnsupportedFormatVersion {
+        /// Format version found in the store's `format` file.
+        found: u32,
+        /// Newest format version this binary knows how to read.
+        supported: u32,
+    },
+    /// A `sled` operation failed, surfaced by `SledKvsEngine`.
+    #[error("sled error occurred: {0}")]
+    Sled(#[from] sled::Error),
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    // A fresh, empty directory under the system temp dir, unique to the calling test.
+    fn temp_store_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kvs_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn compaction_reclaims_stale_records() {
+        let dir = temp_store_dir("compaction_reclaims_stale_records");
+
+        let dir_size = || -> u64 {
+            fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().metadata().unwrap().len())
+                .sum()
+        };
+
+        let mut store = KvStore::open(&dir).unwrap();
+        let value = "v".repeat(2000);
+        for i in 0..1000 {
+            store.set(format!("key{}", i), value.clone()).unwrap();
+        }
+
+        // Overwrite every key several times over; without compaction this would leave
+        // six full copies of the ~2MB live set (~12MB) on disk.
+        for _ in 0..5 {
+            for i in 0..1000 {
+                store.set(format!("key{}", i), value.clone()).unwrap();
+            }
+        }
+        store.flush().unwrap();
+
+        let size = dir_size();
+        assert!(
+            size < 4_000_000,
+            "expected compaction to keep on-disk size close to the ~2MB live set, got {} bytes",
+            size
+        );
+
+        // The live values must still all be readable after compaction.
+        for i in 0..1000 {
+            assert_eq!(store.get(format!("key{}", i)).unwrap(), Some(value.clone()));
+        }
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_recovers_from_a_torn_tail_write() {
+        let dir = temp_store_dir("reopen_recovers_from_a_torn_tail_write");
+
+        {
+            let mut store = KvStore::open(&dir).unwrap();
+            store.set("a".to_owned(), "1".to_owned()).unwrap();
+            store.set("b".to_owned(), "2".to_owned()).unwrap();
+            store.flush().unwrap();
+        }
+
+        // Simulate a crash mid-write of the last record by chopping a few bytes off
+        // the active segment's tail.
+        let log_path = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.extension() == Some(OsStr::new(LOG_FILE_EXT)))
+            .unwrap();
+        let full_len = fs::metadata(&log_path).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&log_path).unwrap();
+        file.set_len(full_len - 3).unwrap();
+        drop(file);
+
+        // open() must recover by truncating the torn record, not error out.
+        let mut store = KvStore::open(&dir).unwrap();
+        assert_eq!(store.get("a".to_owned()).unwrap(), Some("1".to_owned()));
+        assert_eq!(store.get("b".to_owned()).unwrap(), None);
+
+        // And the recovered store must still be writable.
+        store.set("b".to_owned(), "22".to_owned()).unwrap();
+        assert_eq!(store.get("b".to_owned()).unwrap(), Some("22".to_owned()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dump_and_restore_round_trip() {
+        let src_dir = temp_store_dir("dump_and_restore_round_trip_src");
+        let dst_dir = temp_store_dir("dump_and_restore_round_trip_dst");
+        let dump_path = std::env::temp_dir().join("kvs_test_dump_and_restore_round_trip.dump");
+
+        {
+            let mut store = KvStore::open(&src_dir).unwrap();
+            store.set("a".to_owned(), "1".to_owned()).unwrap();
+            store.set("b".to_owned(), "2".to_owned()).unwrap();
+            store.remove("a".to_owned()).unwrap();
+            store.dump(&dump_path).unwrap();
+        }
+
+        let mut dst = KvStore::open(&dst_dir).unwrap();
+        dst.set("stale".to_owned(), "x".to_owned()).unwrap();
+        dst.restore(&dump_path).unwrap();
+
+        assert_eq!(dst.get("a".to_owned()).unwrap(), None);
+        assert_eq!(dst.get("b".to_owned()).unwrap(), Some("2".to_owned()));
+        assert_eq!(dst.get("stale".to_owned()).unwrap(), None);
+
+        fs::remove_dir_all(&src_dir).unwrap();
+        fs::remove_dir_all(&dst_dir).unwrap();
+        fs::remove_file(&dump_path).unwrap();
+    }
 }
@@ -0,0 +1,56 @@
+use crate::engine::KvsEngine;
+use crate::{read_framed, write_framed, Command, Response, Result};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// KvsServer serves a single `KvsEngine` over a length-framed protocol: one connection carries
+/// one framed `Command` in and one framed `Response` back out.
+pub struct KvsServer<E: KvsEngine> {
+    engine: E,
+}
+
+impl<E: KvsEngine> KvsServer<E> {
+    /// Wraps `engine` so it can be served over the network.
+    pub fn new(engine: E) -> KvsServer<E> {
+        KvsServer { engine }
+    }
+
+    /// Binds to `addr` and serves incoming connections until the process exits.
+    ///
+    /// A connection that fails mid-request (a bad frame, a client that closes without
+    /// sending anything) is logged and dropped rather than taking the whole server down.
+    pub fn run<A: ToSocketAddrs>(mut self, addr: A) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            if let Err(e) = self.serve(stream?) {
+                eprintln!("error serving connection: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn serve(&mut self, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        let command: Command = read_framed(&mut reader)?;
+        let response = match command {
+            Command::Get { key } => match self.engine.get(key) {
+                Ok(value) => Response::Ok(value),
+                Err(e) => Response::Err(e.to_string()),
+            },
+            Command::Set { key, value } => match self.engine.set(key, value) {
+                Ok(()) => Response::Ok(None),
+                Err(e) => Response::Err(e.to_string()),
+            },
+            Command::Remove { key } => match self.engine.remove(key) {
+                Ok(()) => Response::Ok(None),
+                Err(e) => Response::Err(e.to_string()),
+            },
+        };
+
+        write_framed(&mut writer, &response)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
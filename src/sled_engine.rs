@@ -0,0 +1,41 @@
+use crate::engine::KvsEngine;
+use crate::{Error, Result};
+use std::path::Path;
+
+/// A `KvsEngine` backed by the `sled` embedded database, as an alternative to the
+/// crate's own bitcask-style `KvStore` for users who want a battle-tested engine.
+pub struct SledKvsEngine {
+    db: sled::Db,
+}
+
+impl SledKvsEngine {
+    /// Opens a sled database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SledKvsEngine> {
+        Ok(SledKvsEngine {
+            db: sled::open(path)?,
+        })
+    }
+}
+
+impl KvsEngine for SledKvsEngine {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.db.insert(key, value.into_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.db.get(key)? {
+            Some(ivec) => String::from_utf8(ivec.to_vec())
+                .map(Some)
+                .map_err(|e| Error::UnexpectedError(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        let removed = self.db.remove(key)?;
+        self.db.flush()?;
+        removed.map(|_| ()).ok_or(Error::KeyNotFound)
+    }
+}